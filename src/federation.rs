@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::Post;
+
+const ACTIVITY_STREAMS_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+
+/// ActivityPub endpoint the local account mirrors its on-chain activity into.
+#[derive(Debug, Clone)]
+pub struct FederationConfig {
+    pub domain: String,
+    pub inbox_url: String
+}
+
+impl FederationConfig {
+    pub fn new(domain: impl Into<String>, inbox_url: impl Into<String>) -> Self {
+        FederationConfig {
+            domain: domain.into(),
+            inbox_url: inbox_url.into()
+        }
+    }
+}
+
+fn actor_url(domain: &str, pubkey: &Pubkey) -> String {
+    format!("https://{}/users/{}", domain, pubkey)
+}
+
+/// WebFinger `acct:` resource for a Solana pubkey, so fediverse servers can resolve the account.
+pub fn webfinger(domain: &str, pubkey: &Pubkey) -> Value {
+    json!({
+        "subject": format!("acct:{}@{}", pubkey, domain),
+        "links": [{
+            "rel": "self",
+            "type": "application/activity+json",
+            "href": actor_url(domain, pubkey)
+        }]
+    })
+}
+
+/// Builds an ActivityStreams `Create` wrapping a `Note` for an on-chain post.
+pub fn to_activity(domain: &str, post: &Post, author: &Pubkey) -> Value {
+    let actor = actor_url(domain, author);
+    let note = json!({
+        "type": "Note",
+        "attributedTo": actor,
+        "content": post.content,
+        "published": to_rfc3339(post.timestamp)
+    });
+    json!({
+        "@context": ACTIVITY_STREAMS_CONTEXT,
+        "type": "Create",
+        "actor": actor,
+        "object": note
+    })
+}
+
+/// Maps `follow_user` onto an ActivityStreams `Follow`.
+pub fn to_follow_activity(domain: &str, actor: &Pubkey, target: &Pubkey) -> Value {
+    json!({
+        "@context": ACTIVITY_STREAMS_CONTEXT,
+        "type": "Follow",
+        "actor": actor_url(domain, actor),
+        "object": actor_url(domain, target)
+    })
+}
+
+/// Maps `unfollow_user` onto `Undo{Follow}`.
+pub fn to_unfollow_activity(domain: &str, actor: &Pubkey, target: &Pubkey) -> Value {
+    json!({
+        "@context": ACTIVITY_STREAMS_CONTEXT,
+        "type": "Undo",
+        "actor": actor_url(domain, actor),
+        "object": to_follow_activity(domain, actor, target)
+    })
+}
+
+fn to_rfc3339(timestamp: u64) -> String {
+    let datetime = DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+    datetime.to_rfc3339()
+}
+
+/// Signs an outbound activity with the user's `Keypair` (HTTP Signatures over
+/// `(request-target)`/`host`/`date`/`digest`) and POSTs it to the target inbox.
+pub fn sign_and_post(config: &FederationConfig, signer: &Keypair, activity: &Value) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::to_vec(activity)?;
+    let digest = format!("SHA-256={}", STANDARD.encode(Sha256::digest(&body)));
+    // HTTP Signatures 要求 IMF-fixdate（RFC 7231），rfc2822 的 "+0000" 尾缀会被部分 inbox 拒收。
+    let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let inbox = url::Url::parse(&config.inbox_url)?;
+    let host = inbox.host_str().ok_or("inbox url missing host")?;
+    let path = inbox.path();
+
+    let signing_string = format!(
+        "(request-target): post {}\nhost: {}\ndate: {}\ndigest: {}",
+        path, host, date, digest
+    );
+    let signature = signer.sign_message(signing_string.as_bytes());
+    let key_id = format!("{}#main-key", actor_url(&config.domain, &signer.pubkey()));
+    let signature_header = format!(
+        "keyId=\"{}\",algorithm=\"ed25519\",headers=\"(request-target) host date digest\",signature=\"{}\"",
+        key_id,
+        STANDARD.encode(signature.as_ref())
+    );
+
+    let client = reqwest::blocking::Client::new();
+    client
+        .post(&config.inbox_url)
+        .header("Host", host)
+        .header("Date", date)
+        .header("Digest", digest)
+        .header("Signature", signature_header)
+        .header("Content-Type", "application/activity+json")
+        .body(body)
+        .send()?;
+    println!("federation: delivered activity to {:?}", config.inbox_url);
+    Ok(())
+}
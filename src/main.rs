@@ -1,13 +1,24 @@
+mod federation;
+
 use std::error::Error;
 use std::str::FromStr;
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::pubsub_client::PubsubClient;
 use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSendTransactionConfig, RpcTransactionLogsConfig,
+    RpcTransactionLogsFilter
+};
 use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::signature::{read_keypair_file, Keypair, Signature};
 use solana_sdk::signer::Signer;
 use solana_sdk::transaction::Transaction;
 
+use federation::FederationConfig;
+
 #[derive(Debug, BorshDeserialize, BorshSerialize)]
 pub enum SocialInstruction {
     Init(String),
@@ -35,6 +46,12 @@ pub struct Post {
     pub timestamp: u64
 }
 
+impl Default for UserProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UserProfile {
 
     pub fn new() -> Self {
@@ -54,19 +71,59 @@ impl UserProfile {
 const USER_PROFILE_SEED: &str = "profile";
 const USER_POST_SEED: &str = "post";
 
+/// Raw, still-undifferentiated account data returned by `getProgramAccounts`.
+type RawAccount = (Pubkey, Vec<u8>);
+
 pub struct SocialClient {
     rpc_client: RpcClient,
-    program_id: Pubkey
+    rpc_url: String,
+    program_id: Pubkey,
+    commitment: CommitmentConfig,
+    skip_preflight: bool,
+    max_retries: Option<usize>,
+    federation: Option<FederationConfig>
+}
+
+#[derive(Debug)]
+pub enum FeedEvent {
+    Post(String),
+    Follow(String),
+    Other(String)
 }
 
 impl SocialClient {
     pub fn new(rpc_url: &str, program_id: Pubkey) -> Self {
         SocialClient {
             rpc_client: RpcClient::new(rpc_url.to_string()),
-            program_id
+            rpc_url: rpc_url.to_string(),
+            program_id,
+            commitment: CommitmentConfig::confirmed(),
+            skip_preflight: false,
+            max_retries: None,
+            federation: None
         }
     }
 
+    pub fn with_commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    pub fn with_skip_preflight(mut self, skip_preflight: bool) -> Self {
+        self.skip_preflight = skip_preflight;
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    pub fn with_federation(mut self, federation: FederationConfig) -> Self {
+        self.federation = Some(federation);
+        self
+    }
+
     pub fn init_user(&self, user: &Keypair, seed_type: &str) -> Result<(), Box<dyn Error>> {
         let social_pda = get_social_pda(&self.program_id, &[user.pubkey().as_ref(), seed_type.as_bytes()]);
         let init_user_data = SocialInstruction::Init(seed_type.to_string());
@@ -98,6 +155,10 @@ impl SocialClient {
         );
         let sign = self.send_instruction(user, vec![follow_user_ins])?;
         println!("follow user success, sign: {:?}", sign);
+        if let Some(config) = &self.federation {
+            let activity = federation::to_follow_activity(&config.domain, &user.pubkey(), &follow_user);
+            federation::sign_and_post(config, user, &activity)?;
+        }
         Ok(())
     }
 
@@ -130,12 +191,16 @@ impl SocialClient {
         );
         let sign = self.send_instruction(user, vec![unfollow_user_ins])?;
         println!("unfollow user success, sign: {:?}", sign);
+        if let Some(config) = &self.federation {
+            let activity = federation::to_unfollow_activity(&config.domain, &user.pubkey(), &follow_user);
+            federation::sign_and_post(config, user, &activity)?;
+        }
         Ok(())
     }
 
     pub fn post(&self, user: &Keypair, content:String, id: u64) -> Result<(), Box<dyn Error>> {
         let social_pda = get_social_pda(&self.program_id, &[user.pubkey().as_ref(), USER_POST_SEED.as_bytes()]);
-        let social_post_pda = get_social_pda(&self.program_id, &[user.pubkey().as_ref(), USER_POST_SEED.as_bytes(), &[id as u8]]);
+        let social_post_pda = get_post_pda(&self.program_id, &user.pubkey(), id)?;
         let user_post_data = SocialInstruction::Post(content);
         let user_post_acc = vec![
             AccountMeta::new(user.pubkey(), true),
@@ -150,12 +215,19 @@ impl SocialClient {
         );
         let sign = self.send_instruction(user, vec![user_post_ins])?;
         println!("user post success, sign: {:?}", sign);
+        if let Some(config) = &self.federation {
+            // `published` 必须来自链上写入的 Post.timestamp，而不是本地时钟，
+            // 所以先把刚写入的账户读回来，用权威的链上记录去构建联邦活动。
+            let post = self.fetch_post(&user.pubkey(), id)?;
+            let activity = federation::to_activity(&config.domain, &post, &user.pubkey());
+            federation::sign_and_post(config, user, &activity)?;
+        }
         Ok(())
     }
 
     pub fn query_post(&self, user: &Keypair, id: u64) -> Result<(), Box<dyn Error>> {
         let social_pda = get_social_pda(&self.program_id, &[user.pubkey().as_ref(), USER_POST_SEED.as_bytes()]);
-        let social_post_pda = get_social_pda(&self.program_id, &[user.pubkey().as_ref(), USER_POST_SEED.as_bytes(), &[id as u8]]);
+        let social_post_pda = get_post_pda(&self.program_id, &user.pubkey(), id)?;
         let query_post_data = SocialInstruction::QueryPosts;
         let query_post_acc = vec![
             AccountMeta::new(social_pda, false),
@@ -171,7 +243,131 @@ impl SocialClient {
         Ok(())
     }
 
-    pub fn send_instruction(&self, payer: &Keypair, instructions: Vec<Instruction>) -> Result<(Signature), Box<dyn Error>> {
+    pub fn fetch_profile(&self, user: &Pubkey) -> Result<UserProfile, Box<dyn Error>> {
+        let social_pda = get_social_pda(&self.program_id, &[user.as_ref(), USER_PROFILE_SEED.as_bytes()]);
+        let account = self.rpc_client.get_account(&social_pda)?;
+        // 账户空间按最大容量分配并用 0 填充尾部，用 deserialize 而非 try_from_slice，
+        // 这样可以正常忽略未写入的尾部字节
+        let profile = UserProfile::deserialize(&mut &account.data[..])?;
+        Ok(profile)
+    }
+
+    pub fn fetch_post(&self, user: &Pubkey, id: u64) -> Result<Post, Box<dyn Error>> {
+        let social_post_pda = get_post_pda(&self.program_id, user, id)?;
+        let account = self.rpc_client.get_account(&social_post_pda)?;
+        let post = Post::deserialize(&mut &account.data[..])?;
+        Ok(post)
+    }
+
+    pub fn next_post_id(&self, user: &Pubkey) -> Result<u64, Box<dyn Error>> {
+        let social_pda = get_social_pda(&self.program_id, &[user.as_ref(), USER_POST_SEED.as_bytes()]);
+        let account = self.rpc_client.get_account(&social_pda)?;
+        let counter = UserPost::deserialize(&mut &account.data[..])?;
+        Ok(counter.post_count)
+    }
+
+    pub fn post_auto(&self, user: &Keypair, content: String) -> Result<(), Box<dyn Error>> {
+        let id = self.next_post_id(&user.pubkey())?;
+        self.post(user, content, id)
+    }
+
+    pub fn fetch_all_posts(&self, user: &Pubkey) -> Result<Vec<Post>, Box<dyn Error>> {
+        let post_count = self.next_post_id(user)?;
+        let mut posts = Vec::with_capacity(post_count as usize);
+        for id in 0..post_count {
+            posts.push(self.fetch_post(user, id)?);
+        }
+        Ok(posts)
+    }
+
+    pub fn all_profiles(&self) -> Result<Vec<(Pubkey, UserProfile)>, Box<dyn Error>> {
+        let accounts = self.get_program_accounts()?;
+        let mut skipped = 0;
+        let profiles = accounts
+            .into_iter()
+            .filter_map(|(pubkey, data)| match classify_program_account(&data) {
+                Some(ProgramAccountKind::Profile(profile)) => Some((pubkey, profile)),
+                _ => {
+                    skipped += 1;
+                    None
+                }
+            })
+            .collect();
+        if skipped > 0 {
+            println!("all_profiles: skipped {} account(s) that did not match the UserProfile layout", skipped);
+        }
+        Ok(profiles)
+    }
+
+    pub fn all_posts(&self) -> Result<Vec<(Pubkey, Post)>, Box<dyn Error>> {
+        let accounts = self.get_program_accounts()?;
+        let mut skipped = 0;
+        let posts = accounts
+            .into_iter()
+            .filter_map(|(pubkey, data)| match classify_program_account(&data) {
+                Some(ProgramAccountKind::Post(post)) => Some((pubkey, post)),
+                _ => {
+                    skipped += 1;
+                    None
+                }
+            })
+            .collect();
+        if skipped > 0 {
+            println!("all_posts: skipped {} account(s) that did not match the Post layout", skipped);
+        }
+        Ok(posts)
+    }
+
+    // 该合约账户没有 anchor 那样的 discriminator 前缀。Solana 的 RpcFilterType 只能表达
+    // "等于" 语义（dataSize 精确匹配、memcmp 精确字节匹配），没有"不等于"，而我们唯一能
+    // 确定的布局事实——UserPost 计数器账户定长且不含变长字段——恰恰需要的是排除而非匹配，
+    // 没法套进这套过滤器；UserProfile/Post 的真实分配容量又完全由链上程序决定，客户端编不出
+    // 可靠的 dataSize/memcmp 值。伪造一个凑巧能用的过滤条件只会把“类型误判”从客户端搬到
+    // RPC 层，并不会更正确。因此这里仍按 program_id 取回全部账户（这本身就是一条隐式的
+    // owner 过滤），再交给 classify_program_account 用布局校验 + 歧义拒绝来分类。
+    fn get_program_accounts(&self) -> Result<Vec<RawAccount>, Box<dyn Error>> {
+        let config = RpcProgramAccountsConfig {
+            filters: None,
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = self
+            .rpc_client
+            .get_program_accounts_with_config(&self.program_id, config)?
+            .into_iter()
+            .map(|(pubkey, account)| (pubkey, account.data))
+            .collect();
+        Ok(accounts)
+    }
+
+    pub fn subscribe_feed(&self, mut on_event: impl FnMut(FeedEvent)) -> Result<(), Box<dyn Error>> {
+        let ws_url = derive_ws_url(&self.rpc_url);
+        let (_subscription, receiver) = PubsubClient::logs_subscribe(
+            &ws_url,
+            RpcTransactionLogsFilter::Mentions(vec![self.program_id.to_string()]),
+            RpcTransactionLogsConfig { commitment: Some(self.commitment) }
+        )?;
+        for response in receiver {
+            for log in response.value.logs {
+                if let Some(message) = log.strip_prefix("Program log: ") {
+                    let event = if message.starts_with("Post") {
+                        FeedEvent::Post(message.to_string())
+                    } else if message.starts_with("Follow") {
+                        FeedEvent::Follow(message.to_string())
+                    } else {
+                        FeedEvent::Other(message.to_string())
+                    };
+                    on_event(event);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn send_instruction(&self, payer: &Keypair, instructions: Vec<Instruction>) -> Result<Signature, Box<dyn Error>> {
         let latest_blockhash = self.rpc_client.get_latest_blockhash()?;
         let tx = Transaction::new_signed_with_payer(
             &instructions,
@@ -179,24 +375,104 @@ impl SocialClient {
             &[payer],
             latest_blockhash
         );
-        let sign = self.rpc_client.send_and_confirm_transaction(&tx)?;
+        let send_config = RpcSendTransactionConfig {
+            skip_preflight: self.skip_preflight,
+            preflight_commitment: Some(self.commitment.commitment),
+            max_retries: self.max_retries,
+            ..RpcSendTransactionConfig::default()
+        };
+        let sign = self.rpc_client.send_and_confirm_transaction_with_spinner_and_config(
+            &tx,
+            self.commitment,
+            send_config
+        )?;
         Ok(sign)
     }
 }
 
 
+fn is_zero_padding(tail: &[u8]) -> bool {
+    tail.iter().all(|b| *b == 0)
+}
+
+enum ProgramAccountKind {
+    Profile(UserProfile),
+    Post(Post)
+}
+
+// UserPost 没有变长字段，borsh 编码长度对任何 post_count 取值都一样，是账户大小里唯一
+// 能确定下来的常量，而不是猜出来的。
+fn user_post_account_len() -> usize {
+    borsh::to_vec(&UserPost { post_count: 0 }).expect("UserPost has no variable-length fields").len()
+}
+
+// 把一条 getProgramAccounts 返回的原始数据分类成 Profile 或 Post。一个全零/刚初始化的
+// UserProfile（0 个 followers）和一个全零/刚初始化的 Post（空 content、timestamp 0）在
+// 字节上是无法区分的——两者都能被对方的类型反序列化成功，尾部也都是零填充。与其像之前
+// 那样默默收下第一个能 parse 的类型，这里要求同一条数据只能唯一地匹配一种布局；如果两种
+// 类型都能匹配（典型地是全零状态），或者两种都匹配不上，一律当作未知账户跳过。
+fn classify_program_account(data: &[u8]) -> Option<ProgramAccountKind> {
+    if data.len() == user_post_account_len() {
+        return None;
+    }
+
+    let profile = {
+        let mut cursor = data;
+        UserProfile::deserialize(&mut cursor)
+            .ok()
+            .filter(|profile| profile.data_len as usize == profile.followers.len() && is_zero_padding(cursor))
+    };
+    let post = {
+        let mut cursor = data;
+        Post::deserialize(&mut cursor).ok().filter(|_| is_zero_padding(cursor))
+    };
+
+    match (profile, post) {
+        (Some(profile), None) => Some(ProgramAccountKind::Profile(profile)),
+        (None, Some(post)) => Some(ProgramAccountKind::Post(post)),
+        _ => None
+    }
+}
+
 fn get_social_pda(program_id: &Pubkey, seed: &[&[u8]]) -> Pubkey {
-    let (social_pda, _bump) = Pubkey::find_program_address(seed, &program_id);
+    let (social_pda, _bump) = Pubkey::find_program_address(seed, program_id);
     println!("social_pda: {:?}", social_pda);
     social_pda
 }
 
+/// 链上程序把帖子 id 按单字节 seed 派生 PDA，客户端必须保持一致，否则算出来的 PDA 会和
+/// 链上账户对不上——哪怕看起来像是换成 `&id.to_le_bytes()` 就能"支持 256 篇以上的帖子"，
+/// 那也只是客户端单方面算出一个不存在的地址，交易会直接失败。
+///
+/// 换句话说：单字节 seed 是 256 篇帖子的硬上限，是协议层的限制，不是这里能修的客户端 bug。
+/// 要真正解除它，必须先升级链上程序把 seed 派生改成完整的 u64，并和所有客户端同步切换；
+/// 在那之前，这个函数能做的只是老实地在 `id > 255` 时报错，而不是假装已经支持了更多帖子。
+fn get_post_pda(program_id: &Pubkey, user: &Pubkey, id: u64) -> Result<Pubkey, Box<dyn Error>> {
+    if id > u8::MAX as u64 {
+        return Err(format!(
+            "post id {} exceeds the on-chain program's 1-byte PDA seed (max {})",
+            id,
+            u8::MAX
+        ).into());
+    }
+    Ok(get_social_pda(program_id, &[user.as_ref(), USER_POST_SEED.as_bytes(), &[id as u8]]))
+}
+
+fn derive_ws_url(rpc_url: &str) -> String {
+    rpc_url
+        .replacen("https://", "wss://", 1)
+        .replacen("http://", "ws://", 1)
+        .replacen(":8899", ":8900", 1)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     // let user_profile = UserProfile::new();
     // print!("user profile len is {:?}", borsh::to_vec(&user_profile).unwrap().len());
     let program_id = Pubkey::from_str("AbiXdK7xj8T3HsgUPKxhYhNQJ8DsujgxeBz9Q8bcfPxu")?;
     let user = read_keypair_file("/home/hantong/.config/solana/id-local.json")?;
-    let social_client = SocialClient::new("http://127.0.0.1:8899", program_id);
+    let social_client = SocialClient::new("http://127.0.0.1:8899", program_id)
+        .with_commitment(CommitmentConfig::processed())
+        .with_skip_preflight(true);
     // 初始化账号
     social_client.init_user(&user, USER_PROFILE_SEED)?;
     let follower_user = Pubkey::from_str("CAz782xYgu4q8zcg5VDafaRLCLDq6FiYPXFSqX5xQtWJ")?;
@@ -208,14 +484,19 @@ fn main() -> Result<(), Box<dyn Error>> {
     social_client.unfollow_user(&user, follower_user)?;
     // 查询关注
     social_client.query_follow(&user)?;
+    // 本地拉取并反序列化 profile，免去一次交易往返
+    let profile = social_client.fetch_profile(&user.pubkey())?;
+    println!("profile: {:?}", profile);
+    // 供 fediverse 服务器解析该账号的 WebFinger 资源
+    println!("webfinger: {}", federation::webfinger("social-cli.example", &user.pubkey()));
 
     // // 初始化post账户
     // social_client.init_user(&user, USER_POST_SEED)?;
-    // // 发送帖子
-    // let id = 1;
+    // // 发送帖子，id 由链上 post_count 计数器自动分配，不再需要手动维护
     // let content = "1: hello".to_string();
-    // social_client.post(&user, content, id)?;
-    // // 查询帖子
-    // social_client.query_post(&user, id)?;
+    // social_client.post_auto(&user, content)?;
+    // // 拉取该用户全部帖子
+    // let posts = social_client.fetch_all_posts(&user.pubkey())?;
+    // println!("posts: {:?}", posts);
     Ok(())
 }